@@ -0,0 +1,101 @@
+//! Contention benchmark comparing a single-locked `LazyPool` against a
+//! `ShardedLazyPool` under concurrent `alloc`/`free` from multiple threads.
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use wasmtime_runtime::instance::allocator::pooling::index_allocator::SlotId;
+use wasmtime_runtime::instance::allocator::pooling::lazy_pool::DecommitStrategy;
+
+const MAX_INSTANCES: usize = 16 * 1024;
+const STACK_SIZE: usize = 1 << 16;
+const OPS_PER_THREAD: usize = 10_000;
+
+fn ids(max_instances: usize) -> Vec<SlotId> {
+    (0..max_instances).map(SlotId).collect()
+}
+
+fn bench_single_lock(c: &mut Criterion, num_threads: usize) {
+    use wasmtime_runtime::instance::allocator::pooling::lazy_pool::LazyPool;
+
+    c.bench_with_input(
+        BenchmarkId::new("single_lock", num_threads),
+        &num_threads,
+        |b, &num_threads| {
+            b.iter(|| {
+                let pool = Arc::new(Mutex::new(LazyPool::new(
+                    ids(MAX_INSTANCES),
+                    MAX_INSTANCES,
+                    STACK_SIZE,
+                    0,
+                    DecommitStrategy::default(),
+                    0,
+                    usize::MAX,
+                )));
+                let handles: Vec<_> = (0..num_threads)
+                    .map(|_| {
+                        let pool = pool.clone();
+                        thread::spawn(move || {
+                            for _ in 0..OPS_PER_THREAD {
+                                let mut pool = pool.lock().unwrap();
+                                let id = pool.alloc().unwrap();
+                                pool.free(id);
+                            }
+                        })
+                    })
+                    .collect();
+                for handle in handles {
+                    handle.join().unwrap();
+                }
+            });
+        },
+    );
+}
+
+fn bench_sharded(c: &mut Criterion, num_threads: usize) {
+    use wasmtime_runtime::instance::allocator::pooling::lazy_pool::ShardedLazyPool;
+
+    c.bench_with_input(
+        BenchmarkId::new("sharded", num_threads),
+        &num_threads,
+        |b, &num_threads| {
+            b.iter(|| {
+                let pool = Arc::new(ShardedLazyPool::new(
+                    ids(MAX_INSTANCES),
+                    MAX_INSTANCES,
+                    STACK_SIZE,
+                    0,
+                    num_threads,
+                    DecommitStrategy::default(),
+                    0,
+                    usize::MAX,
+                ));
+                let handles: Vec<_> = (0..num_threads)
+                    .map(|home| {
+                        let pool = pool.clone();
+                        thread::spawn(move || {
+                            for _ in 0..OPS_PER_THREAD {
+                                let id = pool.alloc(home).unwrap();
+                                pool.free(id);
+                            }
+                        })
+                    })
+                    .collect();
+                for handle in handles {
+                    handle.join().unwrap();
+                }
+            });
+        },
+    );
+}
+
+fn lazy_pool_contention(c: &mut Criterion) {
+    for &num_threads in &[1, 2, 4, 8, 16] {
+        bench_single_lock(c, num_threads);
+        bench_sharded(c, num_threads);
+    }
+}
+
+criterion_group!(benches, lazy_pool_contention);
+criterion_main!(benches);
@@ -1,3 +1,8 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
 use priority_queue::PriorityQueue;
 use slab::Slab;
 
@@ -5,6 +10,41 @@ use super::index_allocator::SlotId;
 
 type Range = (SlotId, SlotId);
 
+/// Selects the madvise behavior used when decommitting dirty stack pages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum DecommitStrategy {
+    /// `MADV_DONTNEED`: reclaim the memory immediately. The next touch
+    /// faults in a fresh zeroed page, so this keeps RSS low at the cost of
+    /// paying that fault on reuse.
+    Immediate,
+    /// `MADV_FREE`: tell the kernel the pages can be reclaimed lazily under
+    /// memory pressure. Cheaper to issue and avoids refaulting if the
+    /// pages are still resident when reused, at the cost of RSS staying
+    /// higher than it needs to be until the kernel actually reclaims.
+    Lazy,
+}
+
+impl Default for DecommitStrategy {
+    fn default() -> Self {
+        DecommitStrategy::Immediate
+    }
+}
+
+/// Decommit stack pages, with a failpoint so tests can inject madvise
+/// failures without actually needing the underlying syscall to fail.
+fn decommit_stack_pages_failable(
+    addr: *mut u8,
+    len: usize,
+    strategy: DecommitStrategy,
+) -> anyhow::Result<()> {
+    #[cfg(feature = "failpoints")]
+    fail::fail_point!("lazy_pool_decommit", |_| Err(anyhow::anyhow!(
+        "injected decommit failure"
+    )));
+
+    crate::instance::allocator::pooling::decommit_stack_pages(addr, len, strategy)
+}
+
 /// LazyPool maintains dirty ranges and clean slots.
 /// To reduce madvise cost, we want to merge continues slots
 /// and do madvise in batch.
@@ -13,6 +53,10 @@ pub(crate) struct LazyPool {
     max_instances: usize,
     stack_size: usize,
     base: usize,
+    strategy: DecommitStrategy,
+    // Max number of slots decommitted per dirty-range pop, bounding the
+    // worst-case madvise work of a single `alloc` fallback.
+    step_size: usize,
 
     // slab id -> range
     dirty_ranges_slab: Slab<Range>,
@@ -23,6 +67,13 @@ pub(crate) struct LazyPool {
     // slab id with priority len_hint
     dirty_ranges: PriorityQueue<usize, usize>,
     clean: Vec<SlotId>,
+
+    // Recently-freed slots kept resident (never decommitted) so a hot
+    // churn of short-lived instances can reuse them without madvise-ing
+    // and refaulting. Bounded to `warm_capacity` entries; anything past
+    // that overflows into the dirty-range merging path.
+    warm: VecDeque<SlotId>,
+    warm_capacity: usize,
 }
 
 impl LazyPool {
@@ -32,56 +83,133 @@ impl LazyPool {
         max_instances: usize,
         stack_size: usize,
         base: usize,
+        strategy: DecommitStrategy,
+        warm_capacity: usize,
+        step_size: usize,
     ) -> Self {
         Self {
             max_instances,
             stack_size,
             base,
+            strategy,
+            step_size,
 
             dirty_ranges_slab: Slab::with_capacity(max_instances),
             dirty_begin_mapping: vec![None; max_instances],
             dirty_end_mapping: vec![None; max_instances],
             dirty_ranges: PriorityQueue::new(),
             clean: ids,
+
+            warm: VecDeque::with_capacity(warm_capacity),
+            warm_capacity,
         }
     }
 
     /// Check if the LazyPool is empty.
     pub(crate) fn is_empty(&self) -> bool {
-        self.clean.is_empty() && self.dirty_ranges.is_empty()
+        self.warm.is_empty() && self.clean.is_empty() && self.dirty_ranges.is_empty()
     }
 
     /// Alloc a clean slot id. Must make sure not empty.
-    pub(crate) fn alloc(&mut self) -> SlotId {
+    ///
+    /// Returns an error if decommitting a dirty range was needed and the
+    /// underlying madvise failed; the pool's dirty-range bookkeeping is left
+    /// unchanged in that case, so the caller can simply retry later.
+    pub(crate) fn alloc(&mut self) -> anyhow::Result<SlotId> {
         debug_assert!(!self.is_empty());
 
+        // Warm slots are still resident and were never decommitted, so
+        // they're the cheapest to hand back.
+        if let Some(id) = self.warm.pop_front() {
+            return Ok(id);
+        }
+
         // try to alloc from clean directly
         if let Some(id) = self.clean.pop() {
-            return id;
+            return Ok(id);
         }
-        // get largest range
-        let (slab_id, _) = self.dirty_ranges.pop().unwrap();
+
+        // Nothing clean: the background worker hasn't caught up (or isn't
+        // running), so fall back to decommitting synchronously on the hot
+        // path.
+        self.decommit_largest_range()?;
+        Ok(self.clean.pop().unwrap())
+    }
+
+    /// Pop the largest dirty range and decommit a bounded slice of it (at
+    /// most `step_size` slots) with madvise, pushing the cleaned slice into
+    /// `clean`. Does nothing if there are no dirty ranges left. Returns the
+    /// number of slots actually decommitted (0 if there was nothing to do).
+    ///
+    /// Only decommitting a slice instead of the whole range bounds the
+    /// worst-case madvise work of a single call to `step_size * stack_size`
+    /// regardless of how large dirty ranges grow, while still coalescing
+    /// physically contiguous stacks into one syscall when a range is
+    /// smaller than `step_size`. Any undecommitted remainder of the range
+    /// is re-inserted with its reduced length as the new priority, so
+    /// later `free`s can still merge into it.
+    ///
+    /// This is the slow path of `alloc`, but it's also what the background
+    /// decommit worker drives off the hot path.
+    ///
+    /// If the decommit fails, the popped range is restored into
+    /// `dirty_ranges_slab`/`dirty_begin_mapping`/`dirty_end_mapping`/
+    /// `dirty_ranges` exactly as it was before the pop, so the pool stays
+    /// consistent and the range can be retried.
+    fn decommit_largest_range(&mut self) -> anyhow::Result<usize> {
+        let (slab_id, priority) = match self.dirty_ranges.pop() {
+            Some(entry) => entry,
+            None => return Ok(0),
+        };
         let (left, right) = self.dirty_ranges_slab.remove(slab_id);
         self.dirty_begin_mapping[left.0] = None;
         self.dirty_end_mapping[right.0] = None;
 
-        // clean it with madvise
+        // Only decommit the first `step_size` slots of the range; the rest
+        // is re-queued below.
+        let range_len = right.0 + 1 - left.0;
+        let slice_len = range_len.min(self.step_size.max(1));
+        let slice_end = SlotId(left.0 + slice_len - 1);
+
         let begin = left.0 * self.stack_size + self.base;
-        let len = (right.0 + 1 - left.0) * self.stack_size;
-        let tick = std::time::Instant::now();
-        crate::instance::allocator::pooling::decommit_stack_pages(begin as *mut u8, len).unwrap();
-        // println!("DEBUG: decommit in batch size {}, time {}ms", right.0 + 1 - left.0, tick.elapsed().as_millis());
-
-        // put them to clean
-        let ret = left;
-        for id in left.0 + 1..=right.0 {
+        let len = slice_len * self.stack_size;
+        if let Err(err) = decommit_stack_pages_failable(begin as *mut u8, len, self.strategy) {
+            let slab_id = self.dirty_ranges_slab.insert((left, right));
+            self.dirty_begin_mapping[left.0] = Some(slab_id);
+            self.dirty_end_mapping[right.0] = Some(slab_id);
+            self.dirty_ranges.push(slab_id, priority);
+            return Err(err);
+        }
+
+        // hand back the cleaned slice
+        for id in left.0..=slice_end.0 {
             self.clean.push(SlotId(id));
         }
-        ret
+
+        // re-insert the still-dirty remainder, if any, with its reduced
+        // length as the new priority
+        if slice_end.0 < right.0 {
+            let new_left = SlotId(slice_end.0 + 1);
+            let new_slab_id = self.dirty_ranges_slab.insert((new_left, right));
+            self.dirty_begin_mapping[new_left.0] = Some(new_slab_id);
+            self.dirty_end_mapping[right.0] = Some(new_slab_id);
+            self.dirty_ranges
+                .push(new_slab_id, right.0 - new_left.0 + 1);
+        }
+
+        Ok(slice_len)
     }
 
     /// Free a slot id.
     pub(crate) fn free(&mut self, index: SlotId) {
+        // Keep the first `warm_capacity` freed slots resident instead of
+        // ever entering the dirty-range bookkeeping; only the overflow
+        // past that gets merged and eventually decommitted.
+        if self.warm.len() < self.warm_capacity {
+            self.warm.push_back(index);
+            return;
+        }
+
         let (mut slab_left, mut slab_right) = (None, None);
         // check prev and next
         if index.0 > 0 {
@@ -107,32 +235,493 @@ impl LazyPool {
                 self.dirty_end_mapping[index.0] = Some(slab_id);
                 let range = unsafe { self.dirty_ranges_slab.get_unchecked_mut(slab_id) };
                 range.1 = index;
-                let size = range.1 .0 - range.0 .0;
-                if size & 0x11111 == 0 {
-                    self.dirty_ranges.change_priority(&slab_id, size);
-                }
+                let size = range.1 .0 - range.0 .0 + 1;
+                self.dirty_ranges.change_priority(&slab_id, size);
             }
             (None, Some(slab_id)) => {
                 // merge with right
                 self.dirty_begin_mapping[index.0] = Some(slab_id);
                 let range = unsafe { self.dirty_ranges_slab.get_unchecked_mut(slab_id) };
                 range.0 = index;
-                let size = range.1 .0 - range.0 .0;
-                if size & 0x11111 == 0 {
-                    self.dirty_ranges.change_priority(&slab_id, size);
-                }
+                let size = range.1 .0 - range.0 .0 + 1;
+                self.dirty_ranges.change_priority(&slab_id, size);
             }
             (Some(left_slab_id), Some(right_slab_id)) => {
                 // merge with left and right
                 let right_range = self.dirty_ranges_slab.remove(right_slab_id);
                 let range = unsafe { self.dirty_ranges_slab.get_unchecked_mut(left_slab_id) };
                 range.1 = right_range.1;
-                let size = range.1 .0 - range.0 .0;
-                if size & 0x11111 == 0 {
-                    self.dirty_ranges.change_priority(&left_slab_id, size);
-                }
+                let size = range.1 .0 - range.0 .0 + 1;
+                self.dirty_ranges.change_priority(&left_slab_id, size);
                 self.dirty_ranges.remove(&right_slab_id);
+                // the merged range now ends where the right one did
+                self.dirty_end_mapping[right_range.1 .0] = Some(left_slab_id);
+            }
+        }
+    }
+
+    /// Assert that the pool's internal bookkeeping is self-consistent.
+    /// Used by fuzzing/tests to catch range-merging bugs that only show up
+    /// after specific `alloc`/`free` interleavings.
+    #[cfg(any(test, feature = "fuzzing"))]
+    pub(crate) fn check_invariants(&self) {
+        use std::collections::HashSet;
+
+        let mut dirty_ids: HashSet<usize> = HashSet::new();
+        for (slab_id, &(left, right)) in self.dirty_ranges_slab.iter() {
+            assert!(left.0 <= right.0, "range {:?} is inverted", (left, right));
+            assert_eq!(
+                self.dirty_begin_mapping[left.0],
+                Some(slab_id),
+                "dirty_begin_mapping[{}] doesn't point back at its range's slab id",
+                left.0
+            );
+            assert_eq!(
+                self.dirty_end_mapping[right.0],
+                Some(slab_id),
+                "dirty_end_mapping[{}] doesn't point back at its range's slab id",
+                right.0
+            );
+
+            let (_, priority) = self
+                .dirty_ranges
+                .get(&slab_id)
+                .unwrap_or_else(|| panic!("slab id {} missing from dirty_ranges", slab_id));
+            assert_eq!(
+                *priority,
+                right.0 - left.0 + 1,
+                "dirty_ranges priority for slab {} is stale",
+                slab_id
+            );
+
+            for id in left.0..=right.0 {
+                assert!(
+                    dirty_ids.insert(id),
+                    "id {} is covered by more than one dirty range",
+                    id
+                );
+            }
+        }
+
+        // No two ranges may overlap or be adjacent: adjacency must always
+        // be merged into a single range.
+        let mut ranges: Vec<Range> = self
+            .dirty_ranges_slab
+            .iter()
+            .map(|(_, &range)| range)
+            .collect();
+        ranges.sort_by_key(|&(left, _)| left.0);
+        for pair in ranges.windows(2) {
+            let (_, prev_right) = pair[0];
+            let (next_left, _) = pair[1];
+            assert!(
+                next_left.0 > prev_right.0 + 1,
+                "ranges ending at {} and starting at {} overlap or are adjacent",
+                prev_right.0,
+                next_left.0
+            );
+        }
+
+        assert!(
+            self.warm.len() <= self.warm_capacity,
+            "warm ring holds {} entries but capacity is {}",
+            self.warm.len(),
+            self.warm_capacity
+        );
+
+        let mut clean_ids: HashSet<usize> = HashSet::new();
+        for id in self.warm.iter().chain(self.clean.iter()) {
+            assert!(
+                clean_ids.insert(id.0),
+                "id {} appears more than once across warm/clean",
+                id.0
+            );
+            assert!(
+                !dirty_ids.contains(&id.0),
+                "id {} is in both warm/clean and a dirty range",
+                id.0
+            );
+        }
+
+        assert_eq!(
+            self.dirty_ranges_slab.len(),
+            self.dirty_ranges.len(),
+            "dirty_ranges_slab and dirty_ranges got out of sync"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(feature = "failpoints")]
+    fn decommit_failure_restores_state_and_is_retryable() {
+        const MAX_INSTANCES: usize = 4;
+        const STACK_SIZE: usize = 4096;
+
+        // Back the pool with a real anonymous mapping so that the
+        // non-failpoint `alloc` below (which issues a real madvise) has
+        // valid memory to operate on.
+        let region_len = MAX_INSTANCES * STACK_SIZE;
+        let base_ptr = unsafe {
+            rustix::mm::mmap_anonymous(
+                std::ptr::null_mut(),
+                region_len,
+                rustix::mm::ProtFlags::READ | rustix::mm::ProtFlags::WRITE,
+                rustix::mm::MapFlags::PRIVATE,
+            )
+            .expect("failed to map backing memory for the test")
+        };
+
+        let mut pool = LazyPool::new(
+            Vec::new(),
+            MAX_INSTANCES,
+            STACK_SIZE,
+            base_ptr as usize,
+            DecommitStrategy::default(),
+            0,
+            usize::MAX,
+        );
+        for id in 0..MAX_INSTANCES {
+            pool.free(SlotId(id));
+        }
+        pool.check_invariants();
+
+        let scenario = fail::FailScenario::setup();
+        fail::cfg("lazy_pool_decommit", "return").unwrap();
+
+        let err = pool.alloc().expect_err("madvise should be failing");
+        assert!(err.to_string().contains("injected decommit failure"));
+        pool.check_invariants();
+
+        fail::cfg("lazy_pool_decommit", "off").unwrap();
+        scenario.teardown();
+
+        pool.alloc()
+            .expect("the dirty range should be allocatable again once the failpoint is disabled");
+        pool.check_invariants();
+
+        unsafe {
+            rustix::mm::munmap(base_ptr, region_len).unwrap();
+        }
+    }
+
+    #[test]
+    fn warm_ring_holds_first_frees_and_overflow_goes_dirty() {
+        const MAX_INSTANCES: usize = 4;
+        const STACK_SIZE: usize = 4096;
+        const WARM_CAPACITY: usize = 2;
+
+        let mut pool = LazyPool::new(
+            Vec::new(),
+            MAX_INSTANCES,
+            STACK_SIZE,
+            0,
+            DecommitStrategy::default(),
+            WARM_CAPACITY,
+            usize::MAX,
+        );
+
+        // The first `WARM_CAPACITY` frees should land in the warm ring
+        // without ever touching the dirty-range bookkeeping.
+        pool.free(SlotId(0));
+        pool.free(SlotId(1));
+        assert_eq!(pool.warm.len(), WARM_CAPACITY);
+        assert!(pool.dirty_ranges_slab.is_empty());
+        pool.check_invariants();
+
+        // `alloc` must drain the warm ring (in FIFO order) before ever
+        // falling back to clean/dirty.
+        assert_eq!(pool.alloc().unwrap(), SlotId(0));
+        assert_eq!(pool.alloc().unwrap(), SlotId(1));
+        assert!(pool.is_empty());
+        pool.check_invariants();
+
+        // Once the ring is full, further frees must overflow into the
+        // dirty-range merge path instead of growing past warm_capacity.
+        pool.free(SlotId(2));
+        pool.free(SlotId(3));
+        assert_eq!(pool.warm.len(), WARM_CAPACITY);
+        pool.free(SlotId(0));
+        assert_eq!(pool.warm.len(), WARM_CAPACITY);
+        assert_eq!(pool.dirty_ranges_slab.len(), 1);
+        pool.check_invariants();
+    }
+
+    #[test]
+    fn sharded_pool_allocates_and_steals_across_uneven_shards() {
+        const MAX_INSTANCES: usize = 10;
+        // shard_width = ceil(10 / 3) = 4: shards own ids 0..=3 and 4..=7,
+        // and the uneven last shard owns just 8..=9 (width 2).
+        const NUM_SHARDS: usize = 3;
+        const STACK_SIZE: usize = 4096;
+
+        // The refill below goes through the dirty-range path, which issues
+        // a real madvise, so back the pool with a real anonymous mapping.
+        let region_len = MAX_INSTANCES * STACK_SIZE;
+        let base_ptr = unsafe {
+            rustix::mm::mmap_anonymous(
+                std::ptr::null_mut(),
+                region_len,
+                rustix::mm::ProtFlags::READ | rustix::mm::ProtFlags::WRITE,
+                rustix::mm::MapFlags::PRIVATE,
+            )
+            .expect("failed to map backing memory for the test")
+        };
+
+        let ids: Vec<SlotId> = (0..MAX_INSTANCES).map(SlotId).collect();
+        let pool = ShardedLazyPool::new(
+            ids,
+            MAX_INSTANCES,
+            STACK_SIZE,
+            base_ptr as usize,
+            NUM_SHARDS,
+            DecommitStrategy::default(),
+            0,
+            usize::MAX,
+        );
+
+        // Always ask for shard 0's home: once it (and then shard 1) empties
+        // out, `alloc` must steal from the next shard instead of panicking,
+        // which exercises the uneven, narrower last shard too.
+        let mut allocated: Vec<usize> = (0..MAX_INSTANCES)
+            .map(|_| pool.alloc(0).unwrap().0)
+            .collect();
+        allocated.sort_unstable();
+        assert_eq!(allocated, (0..MAX_INSTANCES).collect::<Vec<_>>());
+        assert!(pool.is_empty());
+
+        // `free` must route each id back to the shard that actually owns
+        // its range, including the narrower last shard.
+        for &id in &allocated {
+            pool.free(SlotId(id));
+        }
+        assert!(!pool.is_empty());
+
+        // The pool should be fully reusable after a complete drain and
+        // refill.
+        let mut refilled: Vec<usize> = (0..MAX_INSTANCES)
+            .map(|_| pool.alloc(0).unwrap().0)
+            .collect();
+        refilled.sort_unstable();
+        assert_eq!(refilled, (0..MAX_INSTANCES).collect::<Vec<_>>());
+        assert!(pool.is_empty());
+
+        unsafe {
+            rustix::mm::munmap(base_ptr, region_len).unwrap();
+        }
+    }
+}
+
+/// Configuration for the background decommit worker spawned by
+/// [`DecommitWorker::start`].
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct DecommitWorkerConfig {
+    /// How often the worker wakes up to drain `dirty_ranges`.
+    pub interval: Duration,
+    /// Maximum number of slots (stacks) decommitted per wake-up. This is a
+    /// real slot-count budget, independent of `step_size`: the worker keeps
+    /// popping dirty ranges (each still individually capped at `step_size`
+    /// slots) until the running total for this tick reaches
+    /// `slots_per_tick`, bounding the worst-case madvise work a single tick
+    /// can do regardless of how `step_size` is configured.
+    pub slots_per_tick: usize,
+}
+
+impl Default for DecommitWorkerConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_millis(100),
+            slots_per_tick: 256,
+        }
+    }
+}
+
+/// A background thread that periodically drains `LazyPool`'s dirty ranges
+/// so that `alloc` usually finds an already-decommitted slot in `clean`
+/// instead of paying the madvise cost on the hot path.
+pub(crate) struct DecommitWorker {
+    stop: Arc<AtomicBool>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl DecommitWorker {
+    /// Spawn the worker against a shared, externally-locked `LazyPool`.
+    pub(crate) fn start(pool: Arc<Mutex<LazyPool>>, config: DecommitWorkerConfig) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_handle = stop.clone();
+        let thread = std::thread::spawn(move || {
+            while !stop_handle.load(Ordering::Relaxed) {
+                std::thread::sleep(config.interval);
+                let mut pool = pool.lock().unwrap();
+                let mut decommitted = 0;
+                while decommitted < config.slots_per_tick {
+                    // Cap this pop at what is left of the tick's budget so
+                    // the tick never decommits more than `slots_per_tick`.
+                    let step_size = pool.step_size;
+                    pool.step_size = step_size.min(config.slots_per_tick - decommitted);
+                    let result = pool.decommit_largest_range();
+                    pool.step_size = step_size;
+                    // A failed decommit leaves the range intact for the
+                    // next tick (or a synchronous `alloc`) to retry, so
+                    // it's safe to just stop this tick early.
+                    match result {
+                        Ok(0) => break,
+                        Ok(n) => decommitted += n,
+                        Err(_) => break,
+                    }
+                }
+            }
+        });
+        Self {
+            stop,
+            thread: Some(thread),
+        }
+    }
+
+    /// Signal the worker to stop and wait for it to exit.
+    pub(crate) fn stop(mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for DecommitWorker {
+    fn drop(&mut self) {
+        // Make sure the thread doesn't outlive the handle even if `stop`
+        // was never called explicitly.
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// A `LazyPool` partitioned into independently-locked shards so that
+/// concurrent instantiations on different cores don't serialize on a
+/// single mutex. Each shard owns a contiguous sub-range of the slot id
+/// space, so range-merging in `free` only ever needs to look at ids the
+/// shard itself owns.
+#[derive(Debug)]
+pub(crate) struct ShardedLazyPool {
+    shards: Vec<Arc<Mutex<LazyPool>>>,
+    // number of ids owned by each shard (the last shard may own fewer).
+    shard_width: usize,
+}
+
+impl ShardedLazyPool {
+    /// Create a `ShardedLazyPool` over `num_shards` shards, splitting `ids`
+    /// and the `max_instances` id space evenly between them.
+    pub(crate) fn new(
+        ids: Vec<SlotId>,
+        max_instances: usize,
+        stack_size: usize,
+        base: usize,
+        num_shards: usize,
+        strategy: DecommitStrategy,
+        warm_capacity: usize,
+        step_size: usize,
+    ) -> Self {
+        let num_shards = num_shards.max(1);
+        let shard_width = (max_instances + num_shards - 1) / num_shards;
+        // Split `warm_capacity` into a true shared budget across shards
+        // (rather than handing every shard `ceil(warm_capacity /
+        // num_shards)`, which would let total warm capacity exceed what the
+        // caller asked for): each shard gets the base share, and the first
+        // `warm_capacity % num_shards` shards get one extra so the shares
+        // sum to exactly `warm_capacity`.
+        let warm_capacity_base = warm_capacity / num_shards;
+        let warm_capacity_remainder = warm_capacity % num_shards;
+
+        let mut ids_by_shard: Vec<Vec<SlotId>> = vec![Vec::new(); num_shards];
+        for id in ids {
+            let shard = (id.0 / shard_width).min(num_shards - 1);
+            ids_by_shard[shard].push(SlotId(id.0 - shard * shard_width));
+        }
+
+        let shards = ids_by_shard
+            .into_iter()
+            .enumerate()
+            .map(|(shard, ids)| {
+                let start = shard * shard_width;
+                let width = shard_width.min(max_instances.saturating_sub(start));
+                let warm_capacity_for_shard = warm_capacity_base
+                    + if shard < warm_capacity_remainder {
+                        1
+                    } else {
+                        0
+                    };
+                Arc::new(Mutex::new(LazyPool::new(
+                    ids,
+                    width,
+                    stack_size,
+                    base + start * stack_size,
+                    strategy,
+                    warm_capacity_for_shard,
+                    step_size,
+                )))
+            })
+            .collect();
+
+        Self {
+            shards,
+            shard_width,
+        }
+    }
+
+    /// Spawn one background decommit worker per shard, each draining only
+    /// its own shard's dirty ranges. Returns the handles in shard order;
+    /// dropping (or calling `DecommitWorker::stop` on) a handle stops just
+    /// that shard's worker, leaving the others running.
+    pub(crate) fn start_workers(&self, config: DecommitWorkerConfig) -> Vec<DecommitWorker> {
+        self.shards
+            .iter()
+            .map(|shard| DecommitWorker::start(shard.clone(), config))
+            .collect()
+    }
+
+    /// Check if every shard is empty.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.shards
+            .iter()
+            .all(|shard| shard.lock().unwrap().is_empty())
+    }
+
+    /// Alloc a slot id, trying `home`'s shard first (typically a core or
+    /// thread id hash) and only stealing from other shards if its own is
+    /// empty. Must make sure the pool as a whole is not empty.
+    pub(crate) fn alloc(&self, home: usize) -> anyhow::Result<SlotId> {
+        let home_shard = home % self.shards.len();
+        if let Some(id) = self.try_alloc_from(home_shard)? {
+            return Ok(id);
+        }
+        for offset in 1..self.shards.len() {
+            let shard = (home_shard + offset) % self.shards.len();
+            if let Some(id) = self.try_alloc_from(shard)? {
+                return Ok(id);
             }
         }
+        unreachable!("ShardedLazyPool::alloc called while empty");
+    }
+
+    fn try_alloc_from(&self, shard: usize) -> anyhow::Result<Option<SlotId>> {
+        let mut pool = self.shards[shard].lock().unwrap();
+        if pool.is_empty() {
+            return Ok(None);
+        }
+        let local = pool.alloc()?;
+        Ok(Some(SlotId(local.0 + shard * self.shard_width)))
+    }
+
+    /// Free a slot id. Always routed to the shard that owns its id range
+    /// so that range-merging of adjacent slots stays correct.
+    pub(crate) fn free(&self, index: SlotId) {
+        let shard = (index.0 / self.shard_width).min(self.shards.len() - 1);
+        let local = SlotId(index.0 - shard * self.shard_width);
+        self.shards[shard].lock().unwrap().free(local);
     }
 }
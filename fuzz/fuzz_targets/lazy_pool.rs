@@ -0,0 +1,59 @@
+//! Fuzz target that drives `LazyPool` through random `alloc`/`free`
+//! sequences and checks its structural invariants after every step.
+//!
+//! This exercises the `free` merge logic (the four-way match over
+//! left/right neighbors) against random interleavings, which is the part
+//! of `LazyPool` most likely to regress silently. `warm_capacity` is
+//! nonzero so the warm ring (and its overflow into that same merge logic)
+//! actually gets exercised too, instead of every free going straight to
+//! the dirty-range path.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use wasmtime_runtime::instance::allocator::pooling::index_allocator::SlotId;
+use wasmtime_runtime::instance::allocator::pooling::lazy_pool::{DecommitStrategy, LazyPool};
+
+const MAX_INSTANCES: usize = 64;
+const STACK_SIZE: usize = 1 << 16;
+const WARM_CAPACITY: usize = 8;
+
+#[derive(Debug, arbitrary::Arbitrary)]
+enum Op {
+    Alloc,
+    Free(u8),
+}
+
+fuzz_target!(|ops: Vec<Op>| {
+    let ids = (0..MAX_INSTANCES).map(SlotId).collect();
+    let mut pool = LazyPool::new(
+        ids,
+        MAX_INSTANCES,
+        STACK_SIZE,
+        0,
+        DecommitStrategy::default(),
+        WARM_CAPACITY,
+        4,
+    );
+    let mut allocated: Vec<SlotId> = Vec::new();
+
+    for op in ops {
+        match op {
+            Op::Alloc => {
+                if !pool.is_empty() {
+                    if let Ok(id) = pool.alloc() {
+                        allocated.push(id);
+                    }
+                }
+            }
+            Op::Free(index) => {
+                if !allocated.is_empty() {
+                    let index = index as usize % allocated.len();
+                    let id = allocated.swap_remove(index);
+                    pool.free(id);
+                }
+            }
+        }
+        pool.check_invariants();
+    }
+});